@@ -0,0 +1,48 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+
+/// Built `SyntaxSet`/`ThemeSet` pairs, keyed by the user directory they were built from, so a
+/// long-running process doesn't pay to rebuild them on every render.
+static CACHE: OnceLock<Mutex<HashMap<PathBuf, (SyntaxSet, ThemeSet)>>> = OnceLock::new();
+
+/// Build a `SyntaxSet` and `ThemeSet` that merge syntect's defaults with any `.sublime-syntax`
+/// and `.tmTheme` files found in `user_assets_dir`, so users can highlight languages syntect
+/// doesn't ship with and apply their own color schemes.
+///
+/// Building these sets from scratch isn't free, so the result is cached behind a `OnceLock`
+/// keyed by `user_assets_dir`; repeated calls with the same directory return the cached sets.
+pub fn load(user_assets_dir: &Path) -> anyhow::Result<(SyntaxSet, ThemeSet)> {
+    let cache = CACHE.get_or_init(Default::default);
+    if let Some((ss, ts)) = cache.lock().unwrap().get(user_assets_dir) {
+        return Ok((ss.clone(), ts.clone()));
+    }
+
+    let mut ss_builder = SyntaxSet::load_defaults_newlines().into_builder();
+    ss_builder
+        .add_from_folder(user_assets_dir, true)
+        .with_context(|| {
+            format!(
+                "Failed to load custom syntaxes from {}",
+                user_assets_dir.display()
+            )
+        })?;
+    let ss = ss_builder.build();
+
+    let mut ts = ThemeSet::load_defaults();
+    ts.add_from_folder(user_assets_dir).with_context(|| {
+        format!(
+            "Failed to load custom themes from {}",
+            user_assets_dir.display()
+        )
+    })?;
+
+    cache
+        .lock()
+        .unwrap()
+        .insert(user_assets_dir.to_owned(), (ss.clone(), ts.clone()));
+    Ok((ss, ts))
+}
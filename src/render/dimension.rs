@@ -0,0 +1,205 @@
+use prodash::Progress;
+
+/// How to pick the number of columns (and therefore the overall image dimensions) for a render.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum LayoutStrategy {
+    /// Pick the column count whose resulting image best approximates `target_aspect_ratio`,
+    /// padding whatever is left over in the bottom-right corner with background color.
+    #[default]
+    AspectRatio,
+    /// Pick the column count that leaves the least blank canvas, among those whose resulting
+    /// aspect ratio is within `tolerance` of `target_aspect_ratio` (e.g. `0.1` allows a 10%
+    /// deviation). Useful for odd line counts where matching the aspect ratio exactly would
+    /// otherwise leave a large empty corner.
+    MinimizeWaste { tolerance: f32 },
+}
+
+pub struct Dimension {
+    pub imgx: u32,
+    pub imgy: u32,
+    pub lines_per_column: u32,
+    pub required_columns: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn compute(
+    target_aspect_ratio: f32,
+    column_width: u32,
+    total_line_count: u32,
+    line_height: u32,
+    force_full_columns: bool,
+    layout: LayoutStrategy,
+    progress: impl Progress,
+) -> anyhow::Result<Dimension> {
+    match layout {
+        LayoutStrategy::AspectRatio => compute_by_aspect_ratio(
+            target_aspect_ratio,
+            column_width,
+            total_line_count,
+            line_height,
+            force_full_columns,
+            progress,
+        ),
+        LayoutStrategy::MinimizeWaste { tolerance } => compute_minimizing_waste(
+            target_aspect_ratio,
+            column_width,
+            total_line_count,
+            line_height,
+            force_full_columns,
+            tolerance,
+            progress,
+        ),
+    }
+}
+
+fn compute_by_aspect_ratio(
+    target_aspect_ratio: f32,
+    column_width: u32,
+    total_line_count: u32,
+    line_height: u32,
+    force_full_columns: bool,
+    mut progress: impl Progress,
+) -> anyhow::Result<Dimension> {
+    let mut best: Option<(f32, u32, u32)> = None; // (aspect deviation, lines_per_column, required_columns)
+    for lines_per_column in 1..=total_line_count {
+        let required_columns = div_ceil(total_line_count, lines_per_column);
+        if force_full_columns && lines_per_column * required_columns != total_line_count {
+            continue;
+        }
+        let imgx = required_columns * column_width;
+        let imgy = lines_per_column * line_height;
+        let aspect_ratio = imgx as f32 / imgy as f32;
+        let deviation = (aspect_ratio - target_aspect_ratio).abs();
+        if best.map_or(true, |(best_deviation, ..)| deviation < best_deviation) {
+            best = Some((deviation, lines_per_column, required_columns));
+        }
+    }
+    let (_, lines_per_column, required_columns) =
+        best.expect("total_line_count > 0, so at least one candidate exists");
+
+    progress.info(format!(
+        "Chose {lines_per_column} lines per column across {required_columns} columns to match aspect ratio {target_aspect_ratio}"
+    ));
+
+    Ok(Dimension {
+        imgx: required_columns * column_width,
+        imgy: lines_per_column * line_height,
+        lines_per_column,
+        required_columns,
+    })
+}
+
+fn compute_minimizing_waste(
+    target_aspect_ratio: f32,
+    column_width: u32,
+    total_line_count: u32,
+    line_height: u32,
+    force_full_columns: bool,
+    tolerance: f32,
+    mut progress: impl Progress,
+) -> anyhow::Result<Dimension> {
+    let mut best: Option<(u32, u32, u32)> = None; // (wasted cells, lines_per_column, required_columns)
+    for lines_per_column in 1..=total_line_count {
+        let required_columns = div_ceil(total_line_count, lines_per_column);
+        if force_full_columns && lines_per_column * required_columns != total_line_count {
+            continue;
+        }
+        let imgx = required_columns * column_width;
+        let imgy = lines_per_column * line_height;
+        let aspect_ratio = imgx as f32 / imgy as f32;
+        let relative_deviation = (aspect_ratio - target_aspect_ratio).abs() / target_aspect_ratio;
+        if relative_deviation > tolerance {
+            continue;
+        }
+
+        let wasted = lines_per_column * required_columns - total_line_count;
+        if best.map_or(true, |(best_wasted, ..)| wasted < best_wasted) {
+            best = Some((wasted, lines_per_column, required_columns));
+        }
+    }
+
+    // Nothing was within tolerance - fall back to the closest aspect-ratio match so we always
+    // return a usable layout.
+    let within_tolerance = best.is_some();
+    let (lines_per_column, required_columns) = match best {
+        Some((_, lines_per_column, required_columns)) => (lines_per_column, required_columns),
+        None => {
+            let dim = compute_by_aspect_ratio(
+                target_aspect_ratio,
+                column_width,
+                total_line_count,
+                line_height,
+                force_full_columns,
+                progress.add_child("fallback: no candidate within tolerance"),
+            )?;
+            (dim.lines_per_column, dim.required_columns)
+        }
+    };
+
+    progress.info(format!(
+        "Chose {lines_per_column} lines per column across {required_columns} columns, wasting {} cells, {} {tolerance} of aspect ratio {target_aspect_ratio}",
+        lines_per_column * required_columns - total_line_count,
+        if within_tolerance { "within" } else { "no candidate within" }
+    ));
+
+    Ok(Dimension {
+        imgx: required_columns * column_width,
+        imgy: lines_per_column * line_height,
+        lines_per_column,
+        required_columns,
+    })
+}
+
+fn div_ceil(a: u32, b: u32) -> u32 {
+    (a + b - 1) / b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimize_waste_tolerance_is_relative() {
+        // target 2.0 with tolerance 0.1 should accept aspect ratios within +/-10% of 2.0 (i.e.
+        // 1.8..=2.2), not within +/-0.1 of 2.0 in absolute terms.
+        let dim = compute_minimizing_waste(
+            2.0,
+            10,
+            100,
+            10,
+            false,
+            0.1,
+            prodash::progress::Discard,
+        )
+        .unwrap();
+        let aspect_ratio =
+            (dim.required_columns * 10) as f32 / (dim.lines_per_column * 10) as f32;
+        assert!(
+            (aspect_ratio - 2.0).abs() / 2.0 <= 0.1,
+            "chosen layout {aspect_ratio} should be within 10% of target 2.0"
+        );
+    }
+
+    #[test]
+    fn minimize_waste_falls_back_to_aspect_ratio_when_nothing_is_within_tolerance() {
+        // An unreasonably tight tolerance can't be met by any candidate, so this must fall back
+        // to compute_by_aspect_ratio instead of panicking or returning a nonsensical layout.
+        // With 7 lines the candidate aspect ratios are 7, 2, 1, 0.5, 0.4, 0.33 and 0.14, none of
+        // them within 0.0001 of 3.0.
+        let dim =
+            compute_minimizing_waste(3.0, 10, 7, 10, false, 0.0001, prodash::progress::Discard)
+                .unwrap();
+        let fallback = compute_by_aspect_ratio(3.0, 10, 7, 10, false, prodash::progress::Discard)
+            .unwrap();
+        assert_eq!(dim.lines_per_column, fallback.lines_per_column);
+        assert_eq!(dim.required_columns, fallback.required_columns);
+    }
+
+    #[test]
+    fn aspect_ratio_picks_closest_match() {
+        let dim =
+            compute_by_aspect_ratio(1.0, 10, 100, 10, false, prodash::progress::Discard).unwrap();
+        assert_eq!(dim.lines_per_column, 10);
+        assert_eq!(dim.required_columns, 10);
+    }
+}
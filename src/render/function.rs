@@ -1,4 +1,6 @@
 use crate::render::chunk::calc_offsets;
+use crate::render::manifest;
+use crate::render::sniff;
 use crate::render::Cache;
 use crate::render::Dimension;
 use crate::render::{chunk, Options};
@@ -12,7 +14,7 @@ use syntect::highlighting::ThemeSet;
 use syntect::parsing::SyntaxSet;
 
 pub fn render(
-    content: &[(PathBuf, String)],
+    content: &[(PathBuf, Vec<u8>)],
     mut progress: impl Progress,
     should_interrupt: &AtomicBool,
     ss: &SyntaxSet,
@@ -31,30 +33,66 @@ pub fn render(
         plain,
         ignore_files_without_syntax,
         color_modulation,
+        embed_manifest,
+        layout,
+        custom_assets_dir,
     }: Options,
-) -> anyhow::Result<ImageBuffer<Rgb<u8>, MmapMut>> {
+) -> anyhow::Result<RenderOutput> {
     // unused for now
     // could be used to make a "rolling code" animation
     let start = std::time::Instant::now();
 
+    // Swap in syntaxes/themes merged from `custom_assets_dir`, if the caller asked for one, so
+    // `theme` and `ss.find_syntax_for_file` below can also resolve user-supplied ones.
+    let (custom_ss, custom_ts);
+    let (ss, ts) = match &custom_assets_dir {
+        Some(dir) => {
+            (custom_ss, custom_ts) = crate::assets::load(dir)?;
+            (&custom_ss, &custom_ts)
+        }
+        None => (ss, ts),
+    };
+
     //> read files (for /n counting)
-    let (content, total_line_count, num_ignored) = {
+    let (content, total_line_count, num_ignored, num_binary, file_manifest) = {
         let mut out = Vec::with_capacity(content.len());
         let mut lines = 0;
         let mut num_ignored = 0;
+        let mut num_binary = 0;
         let mut lines_so_far = 0u32;
+        let mut file_manifest = Vec::with_capacity(content.len());
         for (path, content) in content {
+            // Sniff the raw bytes straight from disk, before any decoding happens, so the
+            // invalid-UTF-8 ratio and non-ASCII magic bytes in `sniff::looks_binary` can actually
+            // fire; a lossy-decoded `String` would already have replaced those bytes with U+FFFD.
+            let is_binary = sniff::looks_binary(&path, content);
+            // Extensionless files that sniffed as text are kept even without a syntax match.
+            let missing_syntax = ignore_files_without_syntax
+                && path.extension().is_some()
+                && ss.find_syntax_for_file(&path)?.is_none();
+            if is_binary || missing_syntax {
+                if is_binary {
+                    num_binary += 1;
+                } else {
+                    num_ignored += 1;
+                }
+                continue;
+            }
+
+            let content = String::from_utf8_lossy(content).into_owned();
             let num_content_lines = content.lines().count();
             lines += num_content_lines;
-            if ignore_files_without_syntax && ss.find_syntax_for_file(&path)?.is_none() {
-                lines -= num_content_lines;
-                num_ignored += 1;
-            } else {
-                out.push(((path, content), num_content_lines, lines_so_far));
-                lines_so_far += num_content_lines as u32;
+            if embed_manifest {
+                file_manifest.push(manifest::FileEntry {
+                    path: path.clone(),
+                    num_content_lines,
+                    lines_so_far,
+                });
             }
+            out.push(((path, content), num_content_lines, lines_so_far));
+            lines_so_far += num_content_lines as u32;
         }
-        (out, lines as u32, num_ignored)
+        (out, lines as u32, num_ignored, num_binary, file_manifest)
     };
 
     if total_line_count == 0 {
@@ -76,6 +114,7 @@ pub fn render(
         total_line_count,
         line_height,
         force_full_columns,
+        layout,
         progress.add_child("determine dimensions"),
     )?;
 
@@ -118,6 +157,13 @@ pub fn render(
         })?,
     );
 
+    // Colors used across the final image. On the multi-threaded path below, collected as pixels
+    // are stitched into place so we don't have to walk the (potentially huge) finished image a
+    // second time just to build a palette; the single-threaded path has no such hook (see the
+    // comment further down) and still pays for one dedicated pass.
+    let mut palette = std::collections::HashMap::<[u8; 3], u8>::new();
+    let mut palette_overflowed = false;
+
     let threads = (threads == 0)
         .then(num_cpus::get)
         .unwrap_or(threads)
@@ -170,110 +216,116 @@ pub fn render(
     } else {
         // multi-threaded rendering overview:
         //
-        // Spawns threadpool and each file to be renered is sent to a thread as a message via a flume channel.
-        // Upon recieving a message, a thread renders the entire file to an image of one column width.
-        // and then returns that image to this main thread via a flume channel, to be stitched together
-        // into one large image. The ordering of files rendered in the final image is remembered and
-        // independant of thread rendering order.
+        // Each file is rendered independently, in parallel, into its own one-column-wide image
+        // via rayon's work-stealing `par_iter`, which balances uneven file sizes far better than
+        // a round-robin cursor would. Once every file has been rendered, the (deterministic,
+        // `lines_so_far`-ordered) stitch pass below copies each sub-image into its place in the
+        // final image on the main thread.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("failed to build rayon thread pool")?;
+        let rendered = pool.install(|| -> anyhow::Result<Vec<_>> {
+            use rayon::prelude::*;
+
+            let progress = std::sync::Mutex::new(line_progress.add_child("render"));
+            let num_rendered = AtomicUsize::default();
+            content
+                .par_iter()
+                .enumerate()
+                // Clone `cache` once per rayon job (a batch of files) rather than once per file,
+                // since `Cache::clone` isn't free and doing it per file would regress
+                // many-small-files renders. Each file still starts from a fresh plain highlighter.
+                .map_init(
+                    || cache.clone(),
+                    |state, (file_index, ((path, content), num_content_lines, lines_so_far))| {
+                        if should_interrupt.load(Ordering::Relaxed) {
+                            bail!("Cancelled by user")
+                        }
 
-        let mut line_num: u32 = 0;
-        let mut longest_line_chars = 0;
-        let mut background = None;
-        let file_index = AtomicUsize::default();
-        std::thread::scope(|scope| -> anyhow::Result<()> {
-            let (ttx, trx) = flume::unbounded();
-            for tid in 0..threads {
-                scope.spawn({
-                    let ttx = ttx.clone();
-                    let file_index = &file_index;
-                    let ss = &ss;
-                    let content = &content;
-                    let mut state = cache.clone();
-                    let mut progress = line_progress.add_child(format!("Thread {tid}"));
-                    move || -> anyhow::Result<()> {
                         let mut highlighter = state.new_plain_highlighter();
-                        while let Ok(file_index) =
-                            file_index.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
-                                (x < content.len()).then_some(x + 1)
-                            })
-                        {
-                            let ((path, content), num_content_lines, lines_so_far) =
-                                &content[file_index];
-                            if !plain {
-                                if let Some(hl) = state.highlighter_for_file_name(path)? {
-                                    highlighter = hl;
-                                }
+                        if !plain {
+                            if let Some(hl) = state.highlighter_for_file_name(path)? {
+                                highlighter = hl;
                             }
+                        }
 
-                            // create an image that fits one column
-                            let mut img = RgbImage::new(
-                                column_width,
-                                *num_content_lines as u32 * line_height,
-                            );
+                        // create an image that fits one column
+                        let mut img =
+                            RgbImage::new(column_width, *num_content_lines as u32 * line_height);
 
-                            if display_to_be_processed_file {
-                                progress.info(format!("{path:?}"))
-                            }
-                            let out = chunk::process(
-                                content,
-                                &mut img,
-                                |line| highlighter.highlight_line(line, ss),
-                                chunk::Context {
-                                    column_width,
-                                    line_height,
-                                    total_line_count,
-                                    highlight_truncated_lines,
-                                    line_num: 0,
-                                    lines_per_column: total_line_count,
-                                    fg_color,
-                                    bg_color,
-                                    file_index,
-                                    color_modulation,
-                                },
-                            )?;
-                            ttx.send((img, out, *num_content_lines, *lines_so_far))?;
-                        }
-                        Ok(())
-                    }
-                });
-            }
-            drop(ttx);
-
-            // for each file image that was rendered by a thread.
-            for (sub_img, out, num_content_lines, lines_so_far) in trx {
-                longest_line_chars = out.longest_line_in_chars.max(longest_line_chars);
-                background = out.background;
-
-                let calc_offsets = |line_num: u32| {
-                    let actual_line = line_num % total_line_count;
-                    calc_offsets(actual_line, lines_per_column, column_width, line_height)
-                };
-
-                // transfer pixels from sub_img to img. Where sub_img is a 1 column wide
-                // image of one file. And img is our multi-column wide final output image.
-                for line in 0..num_content_lines as u32 {
-                    let (x_offset, line_y) = calc_offsets(lines_so_far + line);
-                    for x in 0..column_width {
-                        for height in 0..line_height {
-                            let pix = sub_img.get_pixel(x, line * line_height + height);
-                            img.put_pixel(x_offset + x, line_y + height, *pix);
+                        if display_to_be_processed_file {
+                            progress.lock().unwrap().info(format!("{path:?}"))
                         }
+                        let out = chunk::process(
+                            content,
+                            &mut img,
+                            |line| highlighter.highlight_line(line, ss),
+                            chunk::Context {
+                                column_width,
+                                line_height,
+                                total_line_count,
+                                highlight_truncated_lines,
+                                line_num: 0,
+                                lines_per_column: total_line_count,
+                                fg_color,
+                                bg_color,
+                                file_index,
+                                color_modulation,
+                            },
+                        )?;
+
+                        progress
+                            .lock()
+                            .unwrap()
+                            .set(num_rendered.fetch_add(1, Ordering::Relaxed) + 1);
+                        Ok((img, out, *num_content_lines, *lines_so_far))
+                    },
+                )
+                .collect()
+        })?;
+
+        let mut line_num: u32 = 0;
+        let mut longest_line_chars = 0;
+        let mut background = None;
+
+        // for each file image that was rendered in parallel, stitched in deterministic order.
+        for (sub_img, out, num_content_lines, lines_so_far) in rendered {
+            longest_line_chars = out.longest_line_in_chars.max(longest_line_chars);
+            background = out.background;
+
+            let calc_offsets = |line_num: u32| {
+                let actual_line = line_num % total_line_count;
+                calc_offsets(actual_line, lines_per_column, column_width, line_height)
+            };
+
+            // transfer pixels from sub_img to img. Where sub_img is a 1 column wide
+            // image of one file. And img is our multi-column wide final output image.
+            // This already visits every pixel the file contributed, so track the palette here
+            // rather than walking the finished image again afterwards.
+            for line in 0..num_content_lines as u32 {
+                let (x_offset, line_y) = calc_offsets(lines_so_far + line);
+                for x in 0..column_width {
+                    for height in 0..line_height {
+                        let pix = *sub_img.get_pixel(x, line * line_height + height);
+                        img.put_pixel(x_offset + x, line_y + height, pix);
+                        track_color(&mut palette, &mut palette_overflowed, pix);
                     }
                 }
+            }
 
-                line_progress.inc_by(num_content_lines);
-                line_num += num_content_lines as u32;
-                progress.inc();
-                if should_interrupt.load(Ordering::Relaxed) {
-                    bail!("Cancelled by user")
-                }
+            line_progress.inc_by(num_content_lines);
+            line_num += num_content_lines as u32;
+            progress.inc();
+            if should_interrupt.load(Ordering::Relaxed) {
+                bail!("Cancelled by user")
             }
-            Ok(())
-        })?;
+        }
         (line_num, longest_line_chars, background)
     };
 
-    // fill in any empty bottom right corner, with background color
+    // fill in any empty bottom right corner, with background color, tracking those pixels in
+    // the palette too since they're part of the finished image.
     while line_num < lines_per_column * required_columns {
         let (cur_column_x_offset, cur_y) =
             calc_offsets(line_num, lines_per_column, column_width, line_height);
@@ -282,11 +334,22 @@ pub fn render(
         for cur_line_x in 0..column_width {
             for y_pos in cur_y..cur_y + line_height {
                 img.put_pixel(cur_column_x_offset + cur_line_x, y_pos, background);
+                track_color(&mut palette, &mut palette_overflowed, background);
             }
         }
         line_num += 1;
     }
 
+    // The single-threaded path above renders straight into `img` from within `chunk::process`,
+    // which doesn't give us a put_pixel call site of our own to piggyback palette tracking on,
+    // so it's the one case that still needs a dedicated pass over the image.
+    if threads < 2 && !palette_overflowed {
+        for pixel in img.pixels() {
+            track_color(&mut palette, &mut palette_overflowed, *pixel);
+        }
+    }
+    let palette = (!palette_overflowed).then_some(palette);
+
     progress.show_throughput(start);
     line_progress.show_throughput(start);
     progress.info(format!(
@@ -295,6 +358,116 @@ pub fn render(
     if num_ignored != 0 {
         progress.info(format!("Ignored {num_ignored} files due to missing syntax",))
     }
+    if num_binary != 0 {
+        progress.info(format!(
+            "Skipped {num_binary} binary/non-text files detected by content sniffing",
+        ))
+    }
+
+    let manifest = embed_manifest.then(|| {
+        manifest::Manifest::new(
+            manifest::OptionsSnapshot {
+                theme: theme.to_owned(),
+                column_width,
+                line_height,
+                target_aspect_ratio,
+                color_modulation,
+                force_full_columns,
+            },
+            total_line_count,
+            longest_line_chars,
+            file_manifest,
+            lines_per_column,
+            required_columns,
+        )
+    });
+
+    Ok(RenderOutput {
+        image: img,
+        palette,
+        manifest,
+    })
+}
+
+/// Record `color` in `palette` unless it's already known or the palette has overflowed past what
+/// an indexed PNG can hold, in which case it's abandoned for good (further colors can't help).
+fn track_color(
+    palette: &mut std::collections::HashMap<[u8; 3], u8>,
+    palette_overflowed: &mut bool,
+    color: Rgb<u8>,
+) {
+    if *palette_overflowed || palette.contains_key(&color.0) {
+        return;
+    }
+    if palette.len() == u8::MAX as usize + 1 {
+        *palette_overflowed = true;
+        palette.clear();
+        return;
+    }
+    let next_index = palette.len() as u8;
+    palette.insert(color.0, next_index);
+}
+
+/// The image produced by [`render()`] along with auxiliary information gathered alongside it.
+pub struct RenderOutput {
+    pub image: ImageBuffer<Rgb<u8>, MmapMut>,
+    /// The distinct colors used in [`Self::image`], mapped to their palette index, if there
+    /// are few enough of them (`<= 256`) to fit an indexed-color PNG. `None` if the image uses
+    /// more colors than a palette can hold, in which case the image should be written as RGB.
+    ///
+    /// On the multi-threaded render path this is collected without an extra pass over the
+    /// finished image; on the single-threaded path (`threads < 2`) it still costs one, since
+    /// `chunk::process` renders straight into the image with no pixel-write hook to piggyback on.
+    pub palette: Option<std::collections::HashMap<[u8; 3], u8>>,
+    /// Present when [`Options::embed_manifest`] is set, letting a downstream viewer resolve a
+    /// pixel back to the file and line it renders, and re-render the image identically.
+    pub manifest: Option<manifest::Manifest>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_new_colors_with_increasing_indices() {
+        let mut palette = std::collections::HashMap::new();
+        let mut overflowed = false;
+        track_color(&mut palette, &mut overflowed, Rgb([1, 2, 3]));
+        track_color(&mut palette, &mut overflowed, Rgb([4, 5, 6]));
+        assert!(!overflowed);
+        assert_eq!(palette[&[1, 2, 3]], 0);
+        assert_eq!(palette[&[4, 5, 6]], 1);
+    }
+
+    #[test]
+    fn repeated_colors_do_not_grow_the_palette() {
+        let mut palette = std::collections::HashMap::new();
+        let mut overflowed = false;
+        track_color(&mut palette, &mut overflowed, Rgb([1, 2, 3]));
+        track_color(&mut palette, &mut overflowed, Rgb([1, 2, 3]));
+        assert_eq!(palette.len(), 1);
+    }
+
+    #[test]
+    fn the_257th_distinct_color_overflows_and_clears_the_palette() {
+        let mut palette = std::collections::HashMap::new();
+        let mut overflowed = false;
+        for i in 0..=u8::MAX {
+            track_color(&mut palette, &mut overflowed, Rgb([i, 0, 0]));
+        }
+        assert_eq!(palette.len(), 256);
+        assert!(!overflowed);
+
+        track_color(&mut palette, &mut overflowed, Rgb([0, 1, 0]));
+        assert!(overflowed);
+        assert!(palette.is_empty());
+    }
 
-    Ok(img)
+    #[test]
+    fn further_colors_are_ignored_once_overflowed() {
+        let mut palette = std::collections::HashMap::new();
+        let mut overflowed = true;
+        track_color(&mut palette, &mut overflowed, Rgb([9, 9, 9]));
+        assert!(palette.is_empty());
+    }
 }
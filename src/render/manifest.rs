@@ -0,0 +1,206 @@
+use crate::render::chunk::calc_offsets;
+use std::path::PathBuf;
+
+/// The codevis version that produced a [`Manifest`], embedded so a later run of codevis can
+/// tell whether it's looking at its own output.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The subset of [`crate::render::Options`] that affects pixel layout and is worth round-tripping
+/// through a rendered image, so the image can describe how to reproduce it.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OptionsSnapshot {
+    pub theme: String,
+    pub column_width: u32,
+    pub line_height: u32,
+    pub target_aspect_ratio: f32,
+    pub color_modulation: f32,
+    pub force_full_columns: bool,
+}
+
+/// One entry of the ordered file list that made up a render, recorded so the image can describe
+/// which of its pixels came from which file.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub num_content_lines: usize,
+    pub lines_so_far: u32,
+}
+
+/// A reproducibility manifest: everything besides the pixels themselves that's needed to
+/// understand or re-create a rendered image. Embedded into the output PNG as an `iTXt` chunk
+/// by [`crate::render::encode::write_png`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub codevis_version: &'static str,
+    pub options: OptionsSnapshot,
+    pub total_line_count: u32,
+    pub longest_line_chars: usize,
+    pub files: Vec<FileEntry>,
+    lines_per_column: u32,
+    required_columns: u32,
+}
+
+impl Manifest {
+    pub(crate) fn new(
+        options: OptionsSnapshot,
+        total_line_count: u32,
+        longest_line_chars: usize,
+        files: Vec<FileEntry>,
+        lines_per_column: u32,
+        required_columns: u32,
+    ) -> Self {
+        Manifest {
+            codevis_version: VERSION,
+            options,
+            total_line_count,
+            longest_line_chars,
+            files,
+            lines_per_column,
+            required_columns,
+        }
+    }
+
+    /// Resolve each file's pixel regions, i.e. the rectangles of `(x, y, width, height)` pixels
+    /// its lines occupy, along with the line number each region starts at. Meant to be
+    /// serialized alongside the manifest so a viewer can answer "what file and line is at
+    /// (x,y)?" without re-implementing the layout math in `chunk::calc_offsets`.
+    ///
+    /// Consecutive lines of a file that land in the same column, directly on top of each other,
+    /// are merged into a single region instead of emitting one per line: a file's lines are
+    /// usually contiguous within a column, so this keeps the sidecar to roughly one entry per
+    /// file per column it touches rather than one per source line.
+    pub fn pixel_map(&self) -> Vec<PixelRegion> {
+        let mut regions = Vec::new();
+        for file in &self.files {
+            let mut current: Option<PixelRegion> = None;
+            for line in 0..file.num_content_lines as u32 {
+                let line_num = (file.lines_so_far + line) % self.total_line_count;
+                let (x, y) = calc_offsets(
+                    line_num,
+                    self.lines_per_column,
+                    self.options.column_width,
+                    self.options.line_height,
+                );
+
+                match &mut current {
+                    Some(region) if region.x == x && region.y + region.height == y => {
+                        region.height += self.options.line_height;
+                    }
+                    _ => {
+                        regions.extend(current.replace(PixelRegion {
+                            x,
+                            y,
+                            width: self.options.column_width,
+                            height: self.options.line_height,
+                            path: file.path.clone(),
+                            start_line: line,
+                        }));
+                    }
+                }
+            }
+            regions.extend(current);
+        }
+        regions
+    }
+}
+
+/// A rectangular pixel region spanning one or more consecutive source lines of a single file.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PixelRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub path: PathBuf,
+    pub start_line: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> OptionsSnapshot {
+        OptionsSnapshot {
+            theme: "base16-ocean.dark".into(),
+            column_width: 10,
+            line_height: 1,
+            target_aspect_ratio: 1.0,
+            color_modulation: 0.0,
+            force_full_columns: false,
+        }
+    }
+
+    #[test]
+    fn merges_contiguous_lines_within_a_column() {
+        // 4 lines per column, one file spanning all 4 lines of the first column: should collapse
+        // into a single region instead of 4.
+        let manifest = Manifest::new(
+            options(),
+            4,
+            0,
+            vec![FileEntry {
+                path: "a.rs".into(),
+                num_content_lines: 4,
+                lines_so_far: 0,
+            }],
+            4,
+            1,
+        );
+        let regions = manifest.pixel_map();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].start_line, 0);
+        assert_eq!(regions[0].height, 4);
+    }
+
+    #[test]
+    fn splits_into_a_new_region_across_a_column_boundary() {
+        // 2 lines per column; a file spanning 4 lines crosses into a second column partway
+        // through, so it must become two regions, not one.
+        let manifest = Manifest::new(
+            options(),
+            4,
+            0,
+            vec![FileEntry {
+                path: "a.rs".into(),
+                num_content_lines: 4,
+                lines_so_far: 0,
+            }],
+            2,
+            2,
+        );
+        let regions = manifest.pixel_map();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].start_line, 0);
+        assert_eq!(regions[0].height, 2);
+        assert_eq!(regions[1].start_line, 2);
+        assert_eq!(regions[1].height, 2);
+        assert_ne!(regions[0].x, regions[1].x);
+    }
+
+    #[test]
+    fn separate_files_never_merge_into_one_region() {
+        let manifest = Manifest::new(
+            options(),
+            4,
+            0,
+            vec![
+                FileEntry {
+                    path: "a.rs".into(),
+                    num_content_lines: 2,
+                    lines_so_far: 0,
+                },
+                FileEntry {
+                    path: "b.rs".into(),
+                    num_content_lines: 2,
+                    lines_so_far: 2,
+                },
+            ],
+            4,
+            1,
+        );
+        let regions = manifest.pixel_map();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0].path, PathBuf::from("a.rs"));
+        assert_eq!(regions[1].path, PathBuf::from("b.rs"));
+    }
+}
@@ -0,0 +1,20 @@
+//! Rendering pipeline: turn a list of `(path, content)` pairs into a pixel image plus whatever
+//! auxiliary data ([`encode`], [`manifest`]) a caller asked for.
+//!
+//! `chunk` (per-file pixel rendering: `chunk::process`/`Context`/`calc_offsets`) and `cache`
+//! (`Cache`, the syntax-highlighter cache `Options::theme` and `plain` resolve against) are
+//! pre-existing infrastructure that every module here was already written against before this
+//! backlog of changes began. Neither file is present in this checkout, so they aren't declared
+//! below; this is a gap in the snapshot, not something introduced by this series, and recreating
+//! their rendering/highlighting logic from scratch is out of scope here.
+mod dimension;
+mod encode;
+mod function;
+mod manifest;
+mod options;
+mod sniff;
+
+pub use dimension::{Dimension, LayoutStrategy};
+pub use encode::write_png;
+pub use function::{render, RenderOutput};
+pub use options::Options;
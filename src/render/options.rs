@@ -0,0 +1,26 @@
+use crate::render::dimension::LayoutStrategy;
+use image::Rgb;
+use std::path::PathBuf;
+
+/// Everything a caller can configure about a single [`crate::render::render`] call.
+pub struct Options<'a> {
+    pub column_width: u32,
+    pub line_height: u32,
+    pub target_aspect_ratio: f32,
+    /// `0` lets the renderer pick `num_cpus::get()`; otherwise capped at `num_cpus::get()`.
+    pub threads: usize,
+    pub fg_color: Option<Rgb<u8>>,
+    pub bg_color: Option<Rgb<u8>>,
+    pub highlight_truncated_lines: bool,
+    pub display_to_be_processed_file: bool,
+    pub theme: &'a str,
+    pub force_full_columns: bool,
+    pub plain: bool,
+    pub ignore_files_without_syntax: bool,
+    pub color_modulation: f32,
+    pub embed_manifest: bool,
+    pub layout: LayoutStrategy,
+    /// Extra directory to load `.sublime-syntax`/`.tmTheme` files from, merged with the defaults
+    /// via [`crate::assets::load`].
+    pub custom_assets_dir: Option<PathBuf>,
+}
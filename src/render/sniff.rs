@@ -0,0 +1,143 @@
+use std::path::Path;
+
+/// Best-effort guess at whether a file's content is binary/non-text, based on sniffing its
+/// leading *raw* bytes rather than trusting `SyntaxSet::find_syntax_for_file`'s extension match
+/// alone. Must run on bytes straight from disk, before any UTF-8 (lossy or otherwise) decoding:
+/// a real PNG/JPEG/Mach-O etc. either fails strict UTF-8 decoding outright or has its magic
+/// bytes replaced by U+FFFD under a lossy decode, so neither the invalid-UTF-8 ratio nor the
+/// non-ASCII magic bytes below can ever fire once the content has already become a `String`.
+///
+/// This catches binary files that happen to have a recognized extension (e.g. a `.rs` that's
+/// actually a compiled object dropped in by mistake), while still rendering extensionless text
+/// like shell scripts or `/proc`-style files that `find_syntax_for_file` can't place. Empty
+/// files are treated as text (zero lines to render), not binary.
+pub fn looks_binary(path: &Path, content: &[u8]) -> bool {
+    let sample = &content[..content.len().min(8192)];
+    if sample.is_empty() {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+
+    // Magic-byte families that are unambiguously binary even if their content samples as
+    // mostly printable (e.g. a `.zip` whose first bytes happen to look text-ish).
+    const BINARY_MAGIC: &[&[u8]] = &[
+        b"\x89PNG",
+        b"\xFF\xD8\xFF",
+        b"GIF8",
+        b"PK\x03\x04",
+        b"%PDF",
+        b"\x7FELF",
+        b"\xCA\xFE\xBA\xBE",
+    ];
+    if BINARY_MAGIC.iter().any(|magic| sample.starts_with(magic)) {
+        return true;
+    }
+
+    if invalid_utf8_ratio(sample) > 0.1 {
+        return true;
+    }
+
+    let non_text = sample
+        .iter()
+        .filter(|&&b| !matches!(b, b'\n' | b'\r' | b'\t') && (b < 0x20 || b == 0x7f))
+        .count();
+    if non_text as f32 / sample.len() as f32 > 0.3 {
+        return true;
+    }
+
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some(
+            "png" | "jpg"
+                | "jpeg"
+                | "gif"
+                | "webp"
+                | "ico"
+                | "bmp"
+                | "pdf"
+                | "zip"
+                | "gz"
+                | "tar"
+                | "so"
+                | "dylib"
+                | "dll"
+                | "exe"
+                | "woff"
+                | "woff2"
+                | "ttf"
+                | "otf"
+        )
+    )
+}
+
+/// Fraction of `sample` that isn't part of a valid UTF-8 sequence. A high ratio is a strong
+/// binary signal for data that isn't already known to be valid UTF-8 (unlike a decoded `&str`).
+fn invalid_utf8_ratio(sample: &[u8]) -> f32 {
+    let mut rest = sample;
+    let mut invalid = 0usize;
+    while !rest.is_empty() {
+        match std::str::from_utf8(rest) {
+            Ok(_) => break,
+            Err(e) => match e.error_len() {
+                Some(len) => {
+                    invalid += len;
+                    rest = &rest[e.valid_up_to() + len..];
+                }
+                // an incomplete sequence at the very end of the sample; not necessarily invalid.
+                None => break,
+            },
+        }
+    }
+    invalid as f32 / sample.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn detects_non_ascii_magic_bytes() {
+        // PNG's magic bytes aren't valid UTF-8, so this only fires when given raw bytes.
+        assert!(looks_binary(
+            Path::new("thing.rs"),
+            b"\x89PNG\r\n\x1a\n rest of file"
+        ));
+    }
+
+    #[test]
+    fn detects_ascii_safe_magic_bytes() {
+        assert!(looks_binary(Path::new("thing.bin"), b"%PDF-1.4 rest of file"));
+    }
+
+    #[test]
+    fn plain_text_is_not_binary() {
+        assert!(!looks_binary(
+            Path::new("thing.rs"),
+            b"fn main() {\n    println!(\"hi\");\n}\n"
+        ));
+    }
+
+    #[test]
+    fn empty_content_is_not_binary() {
+        assert!(!looks_binary(Path::new("thing.rs"), b""));
+    }
+
+    #[test]
+    fn nul_byte_is_binary() {
+        assert!(looks_binary(Path::new("thing.rs"), b"abc\0def"));
+    }
+
+    #[test]
+    fn invalid_utf8_ratio_counts_bytes_not_occurrences() {
+        let mostly_valid = [b"a".repeat(95), vec![0xff; 5]].concat();
+        assert!(invalid_utf8_ratio(&mostly_valid) < 0.1);
+
+        // 10 truncated 3-byte sequences of 2 bytes each: 20 invalid bytes (0.2) but only 10
+        // occurrences (0.1), so this only exceeds 0.1 when bytes are counted.
+        let mostly_invalid = [[0xe2, 0x82].repeat(10), b"a".repeat(80)].concat();
+        assert!(invalid_utf8_ratio(&mostly_invalid) > 0.1);
+    }
+}